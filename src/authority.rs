@@ -0,0 +1,227 @@
+use std::fs;
+use std::io;
+
+use crate::protocol::{DNSAnswer, QueryType, RData};
+
+// A single authoritative zone: its apex name, SOA parameters, and the records it's authoritative
+// for. Lookups are a linear scan since zones loaded from a definition file are expected to be
+// small; nothing here needs to scale to a full production-sized zone.
+pub struct Zone {
+    apex: Vec<String>,
+    m_name: Vec<String>,
+    r_name: Vec<String>,
+    serial: u32,
+    refresh: u32,
+    retry: u32,
+    expire: u32,
+    minimum: u32,
+    records: Vec<DNSAnswer>,
+}
+
+impl Zone {
+    // Returns every record at `name` whose type matches `query_type`.
+    pub fn lookup(&self, name: &[String], query_type: QueryType) -> Vec<&DNSAnswer> {
+        self.records
+            .iter()
+            .filter(|record| record.domain_name.as_slice() == name && record.query_type == query_type)
+            .collect()
+    }
+
+    // True if the zone has any record at all for `name`, regardless of type. Used to tell
+    // NXDOMAIN (the name doesn't exist in the zone) apart from NODATA (it exists, just not with
+    // the requested type).
+    pub fn exists(&self, name: &[String]) -> bool {
+        name == self.apex || self.records.iter().any(|record| record.domain_name == name)
+    }
+
+    // The zone's own SOA record, included in the authority section when a name in the zone has
+    // no records of the requested type.
+    pub fn soa_record(&self) -> DNSAnswer {
+        DNSAnswer {
+            domain_name: self.apex.clone(),
+            query_type: QueryType::SOA,
+            query_class: 1,
+            ttl: self.minimum,
+            rdata: RData::SOA {
+                m_name: self.m_name.clone(),
+                r_name: self.r_name.clone(),
+                serial: self.serial,
+                refresh: self.refresh,
+                retry: self.retry,
+                expire: self.expire,
+                minimum: self.minimum,
+            },
+        }
+    }
+}
+
+// Maps domain suffixes to the zone authoritative for them, loaded once at startup.
+pub struct ZoneRegistry {
+    zones: Vec<Zone>,
+}
+
+impl ZoneRegistry {
+    // Finds the zone whose apex is a suffix of `name`, preferring the most specific (longest
+    // apex) match when zones are nested.
+    pub fn find(&self, name: &[String]) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| is_suffix(&zone.apex, name))
+            .max_by_key(|zone| zone.apex.len())
+    }
+
+    // Parses a zone definition file. Each zone begins with a `$ORIGIN` line naming its apex and a
+    // `$SOA` line giving the SOA fields, followed by `<name> <TYPE> <rdata...>` record lines until
+    // the next `$ORIGIN`. `@` stands for the current apex, and a trailing `.` marks a name as
+    // already fully qualified; anything else is taken as relative to the apex. Blank lines and
+    // lines starting with `;` are ignored.
+    pub fn load(path: &str) -> Result<ZoneRegistry, io::Error> {
+        let contents = fs::read_to_string(path)?;
+        let mut zones = Vec::new();
+        let mut current: Option<ZoneBuilder> = None;
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            match fields.as_slice() {
+                ["$ORIGIN", apex] => {
+                    if let Some(builder) = current.take() {
+                        zones.push(builder.build());
+                    }
+                    current = Some(ZoneBuilder::new(parse_name(apex, &[])));
+                }
+                ["$SOA", m_name, r_name, serial, refresh, retry, expire, minimum] => {
+                    let builder = current.as_mut().ok_or_else(malformed_zone_file)?;
+                    builder.m_name = parse_name(m_name, &builder.apex);
+                    builder.r_name = parse_name(r_name, &builder.apex);
+                    builder.serial = parse_u32(serial)?;
+                    builder.refresh = parse_u32(refresh)?;
+                    builder.retry = parse_u32(retry)?;
+                    builder.expire = parse_u32(expire)?;
+                    builder.minimum = parse_u32(minimum)?;
+                }
+                [name, "A", addr] => {
+                    let builder = current.as_mut().ok_or_else(malformed_zone_file)?;
+                    let addr = addr.parse().map_err(|_| malformed_zone_file())?;
+                    builder.push_record(name, QueryType::A, RData::A(addr));
+                }
+                [name, "AAAA", addr] => {
+                    let builder = current.as_mut().ok_or_else(malformed_zone_file)?;
+                    let addr = addr.parse().map_err(|_| malformed_zone_file())?;
+                    builder.push_record(name, QueryType::AAAA, RData::AAAA(addr));
+                }
+                [name, "NS", target] => {
+                    let builder = current.as_mut().ok_or_else(malformed_zone_file)?;
+                    let target = parse_name(target, &builder.apex);
+                    builder.push_record(name, QueryType::NS, RData::NS(target));
+                }
+                [name, "CNAME", target] => {
+                    let builder = current.as_mut().ok_or_else(malformed_zone_file)?;
+                    let target = parse_name(target, &builder.apex);
+                    builder.push_record(name, QueryType::CNAME, RData::CNAME(target));
+                }
+                [name, "MX", preference, target] => {
+                    let builder = current.as_mut().ok_or_else(malformed_zone_file)?;
+                    let preference = preference.parse().map_err(|_| malformed_zone_file())?;
+                    let target = parse_name(target, &builder.apex);
+                    builder.push_record(name, QueryType::MX, RData::MX(preference, target));
+                }
+                [name, "TXT", text @ ..] => {
+                    let builder = current.as_mut().ok_or_else(malformed_zone_file)?;
+                    let strings = text.iter().map(|s| s.to_string()).collect();
+                    builder.push_record(name, QueryType::TXT, RData::TXT(strings));
+                }
+                _ => return Err(malformed_zone_file()),
+            }
+        }
+
+        if let Some(builder) = current.take() {
+            zones.push(builder.build());
+        }
+
+        Ok(ZoneRegistry { zones })
+    }
+}
+
+// Accumulates a zone's SOA fields and records while its definition is being parsed.
+struct ZoneBuilder {
+    apex: Vec<String>,
+    m_name: Vec<String>,
+    r_name: Vec<String>,
+    serial: u32,
+    refresh: u32,
+    retry: u32,
+    expire: u32,
+    minimum: u32,
+    records: Vec<DNSAnswer>,
+}
+
+impl ZoneBuilder {
+    fn new(apex: Vec<String>) -> ZoneBuilder {
+        ZoneBuilder {
+            apex,
+            m_name: Vec::new(),
+            r_name: Vec::new(),
+            serial: 0,
+            refresh: 0,
+            retry: 0,
+            expire: 0,
+            minimum: 0,
+            records: Vec::new(),
+        }
+    }
+
+    fn push_record(&mut self, name: &str, query_type: QueryType, rdata: RData) {
+        self.records.push(DNSAnswer {
+            domain_name: parse_name(name, &self.apex),
+            query_type,
+            query_class: 1,
+            ttl: self.minimum,
+            rdata,
+        });
+    }
+
+    fn build(self) -> Zone {
+        Zone {
+            apex: self.apex,
+            m_name: self.m_name,
+            r_name: self.r_name,
+            serial: self.serial,
+            refresh: self.refresh,
+            retry: self.retry,
+            expire: self.expire,
+            minimum: self.minimum,
+            records: self.records,
+        }
+    }
+}
+
+fn parse_name(token: &str, apex: &[String]) -> Vec<String> {
+    if token == "@" {
+        return apex.to_vec();
+    }
+    if let Some(fqdn) = token.strip_suffix('.') {
+        return fqdn.split('.').map(str::to_string).collect();
+    }
+    token
+        .split('.')
+        .map(str::to_string)
+        .chain(apex.iter().cloned())
+        .collect()
+}
+
+fn parse_u32(token: &str) -> Result<u32, io::Error> {
+    token.parse().map_err(|_| malformed_zone_file())
+}
+
+fn malformed_zone_file() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed zone definition file")
+}
+
+fn is_suffix(suffix: &[String], name: &[String]) -> bool {
+    suffix.len() <= name.len() && name[name.len() - suffix.len()..] == *suffix
+}
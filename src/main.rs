@@ -1,26 +1,89 @@
+mod authority;
 mod protocol;
+mod resolver;
 
-use std::net::UdpSocket;
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+
+use authority::ZoneRegistry;
+use protocol::{DNSHeader, DNSQuery, DNSResponse};
+
+const UDP_MAX_SIZE: usize = 512;
+const BIND_ADDR: &str = "127.0.0.1:2053";
+
+struct Config {
+    resolver_addr: Option<String>,
+    zones: Option<ZoneRegistry>,
+}
 
 fn main() {
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address");
-    let mut buf = [0; 512];
+    let args: Vec<String> = env::args().collect();
+    let resolver_addr = flag_value(&args, "--resolver");
+    let zones = flag_value(&args, "--zone").map(|path| {
+        ZoneRegistry::load(&path).unwrap_or_else(|e| panic!("Failed to load zone file {path}: {e}"))
+    });
+    let config = Arc::new(Config {
+        resolver_addr,
+        zones,
+    });
+
+    {
+        let config = Arc::clone(&config);
+        thread::spawn(move || run_tcp_server(&config));
+    }
+
+    run_udp_server(&config);
+}
+
+// Parses a query and builds its reply, shared between the UDP and TCP listeners. Returns `None`
+// only when the packet is too malformed to even recover a request ID to reply to. The returned
+// size is the UDP truncation threshold to apply: the client's EDNS0-advertised payload size if it
+// sent one, otherwise the standard 512-byte limit. TCP callers can ignore it.
+fn handle_query(bytes: &[u8], config: &Config) -> Option<(DNSResponse, usize)> {
+    match DNSQuery::from_bytes(bytes) {
+        Ok(query) => {
+            // RFC 6891 §6.2.3: payload sizes below 512 must be treated as 512.
+            let max_size = query
+                .edns_udp_payload_size()
+                .map(|size| (size as usize).max(UDP_MAX_SIZE))
+                .unwrap_or(UDP_MAX_SIZE);
+            let response = DNSResponse::for_request(
+                query,
+                config.resolver_addr.as_deref(),
+                config.zones.as_ref(),
+            );
+            Some((response, max_size))
+        }
+        Err(e) => match DNSHeader::from_bytes(bytes) {
+            Ok(header) => Some((DNSResponse::format_error(header.id()), UDP_MAX_SIZE)),
+            Err(_) => {
+                eprintln!("Error parsing query: {}", e);
+                None
+            }
+        },
+    }
+}
+
+fn run_udp_server(config: &Config) {
+    let udp_socket = UdpSocket::bind(BIND_ADDR).expect("Failed to bind to address");
+    let mut buf = [0; UDP_MAX_SIZE];
 
     loop {
         match udp_socket.recv_from(&mut buf) {
             Ok((size, source)) => {
                 println!("Received {} bytes from {}", size, source);
-                match protocol::DNSQuery::from_bytes(&buf[0..size]) {
-                    Ok(query) => {
-                        let response = protocol::DNSResponse::for_request(query);
-                        udp_socket
-                            .send_to(&response.to_bytes(), source)
-                            .expect("Failed to send response");
-                    }
-                    Err(e) => {
-                        eprintln!("Error parsing query: {}", e);
-                        continue;
+                if let Some((mut response, max_size)) = handle_query(&buf[0..size], config) {
+                    let mut bytes = response.to_bytes();
+                    if bytes.len() > max_size {
+                        response.truncate();
+                        bytes = response.to_bytes();
                     }
+                    udp_socket
+                        .send_to(&bytes, source)
+                        .expect("Failed to send response");
                 }
             }
             Err(e) => {
@@ -30,3 +93,49 @@ fn main() {
         }
     }
 }
+
+fn run_tcp_server(config: &Arc<Config>) {
+    let listener = TcpListener::bind(BIND_ADDR).expect("Failed to bind TCP listener");
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let config = Arc::clone(config);
+                thread::spawn(move || {
+                    if let Err(e) = handle_tcp_connection(stream, &config) {
+                        eprintln!("Error handling TCP connection: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Error accepting TCP connection: {}", e),
+        }
+    }
+}
+
+// Reads length-prefixed DNS messages from `stream` until it closes, replying to each with its
+// own length prefix in turn.
+fn handle_tcp_connection(mut stream: TcpStream, config: &Config) -> io::Result<()> {
+    loop {
+        let mut len_bytes = [0u8; 2];
+        if stream.read_exact(&mut len_bytes).is_err() {
+            return Ok(());
+        }
+        let mut message = vec![0u8; u16::from_be_bytes(len_bytes) as usize];
+        stream.read_exact(&mut message)?;
+
+        if let Some((response, _)) = handle_query(&message, config) {
+            let bytes = response.to_bytes();
+            stream.write_all(&(bytes.len() as u16).to_be_bytes())?;
+            stream.write_all(&bytes)?;
+        }
+    }
+}
+
+// Looks for `<flag> <value>` among the process arguments, e.g. `--resolver 8.8.8.8:53` or
+// `--zone zones.conf`. Returns `None` when the flag is absent.
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
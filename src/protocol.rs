@@ -1,4 +1,5 @@
-use std::net::Ipv4Addr;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 #[derive(Debug, Clone)]
 pub struct ProtocolError;
@@ -9,6 +10,156 @@ impl std::fmt::Display for ProtocolError {
     }
 }
 
+// RFC 1035 caps compression pointer chains implicitly by requiring each pointer to point
+// backwards, but a hostile packet can still point into a loop of forward-looking offsets if we
+// don't enforce it ourselves; bail out after this many indirections.
+const MAX_POINTER_JUMPS: u32 = 16;
+
+// A cursor over an entire DNS message. Keeping the whole datagram around (rather than walking a
+// sub-slice) is what makes following a compression pointer possible: a pointer's offset is always
+// relative to byte 0 of the message, so random-access reads need the full buffer in scope.
+pub struct PacketBuffer<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PacketBuffer<'a> {
+    pub fn new(buf: &'a [u8]) -> PacketBuffer<'a> {
+        PacketBuffer { buf, pos: 0 }
+    }
+
+    #[allow(dead_code)]
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    fn read_u8(&mut self) -> Result<u8, ProtocolError> {
+        let byte = *self.buf.get(self.pos).ok_or(ProtocolError)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, ProtocolError> {
+        let hi = self.read_u8()?;
+        let lo = self.read_u8()?;
+        Ok(u16::from_be_bytes([hi, lo]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, ProtocolError> {
+        let hi = self.read_u16()?;
+        let lo = self.read_u16()?;
+        Ok(((hi as u32) << 16) | lo as u32)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ProtocolError> {
+        let slice = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or(ProtocolError)?;
+        self.pos += len;
+        Ok(slice)
+    }
+
+    // Reads the labels of a domain name starting at the current position, following compression
+    // pointers as they're encountered. Leaves the cursor positioned just past the name as it
+    // appeared at the call site (i.e. past a pointer, not past whatever it points to).
+    pub fn read_labels(&mut self) -> Result<Vec<String>, ProtocolError> {
+        let mut labels = Vec::new();
+        let mut pos = self.pos;
+        let mut jumped = false;
+        let mut jumps = 0;
+
+        loop {
+            let length = *self.buf.get(pos).ok_or(ProtocolError)?;
+
+            if length & 0b1100_0000 == 0b1100_0000 {
+                if jumps >= MAX_POINTER_JUMPS {
+                    return Err(ProtocolError);
+                }
+                let next = *self.buf.get(pos + 1).ok_or(ProtocolError)?;
+                let offset = (((length & 0b0011_1111) as usize) << 8) | next as usize;
+                if offset >= self.buf.len() {
+                    return Err(ProtocolError);
+                }
+                if !jumped {
+                    self.pos = pos + 2;
+                    jumped = true;
+                }
+                pos = offset;
+                jumps += 1;
+                continue;
+            }
+
+            if length == 0 {
+                if !jumped {
+                    self.pos = pos + 1;
+                }
+                break;
+            }
+
+            let label_start = pos + 1;
+            let label_end = label_start + length as usize;
+            let label = self.buf.get(label_start..label_end).ok_or(ProtocolError)?;
+            labels.push(String::from_utf8_lossy(label).to_string());
+            pos = label_end;
+        }
+
+        Ok(labels)
+    }
+}
+
+// Tracks the byte offset at which each name suffix has already been written for a single outgoing
+// message, so later names can emit a pointer back to it instead of repeating labels.
+#[derive(Default)]
+pub struct NameCompressor {
+    offsets: HashMap<Vec<String>, u16>,
+}
+
+impl NameCompressor {
+    pub fn new() -> NameCompressor {
+        NameCompressor::default()
+    }
+
+    // Appends `labels` to `bytes`, pointing at a previously-written suffix when one is available.
+    // `bytes` must already hold everything that precedes this name in the message under
+    // construction, since recorded offsets are relative to the start of the message.
+    pub fn write_name(&mut self, bytes: &mut Vec<u8>, labels: &[String]) {
+        for i in 0..labels.len() {
+            let suffix = &labels[i..];
+            if let Some(&offset) = self.offsets.get(suffix) {
+                let pointer = 0b1100_0000_0000_0000 | offset;
+                bytes.extend(&pointer.to_be_bytes());
+                return;
+            }
+
+            // Pointer offsets only have 14 bits to work with, so suffixes starting past that
+            // point can never be pointed back to; skip recording them.
+            if bytes.len() <= 0x3fff {
+                self.offsets.insert(suffix.to_vec(), bytes.len() as u16);
+            }
+            bytes.push(labels[i].len() as u8);
+            bytes.extend(labels[i].as_bytes());
+        }
+        bytes.push(0x0);
+    }
+
+    // Appends `labels` to `bytes` as plain, uncompressed labels, neither pointing at nor
+    // recording offsets for later names. Some rdata (e.g. SRV's target, per RFC 2782) must never
+    // be compressed.
+    pub fn write_name_uncompressed(bytes: &mut Vec<u8>, labels: &[String]) {
+        for label in labels {
+            bytes.push(label.len() as u8);
+            bytes.extend(label.as_bytes());
+        }
+        bytes.push(0x0);
+    }
+}
+
+#[derive(Clone)]
 pub struct DNSHeader {
     // Packet Identifier (ID) - 16 bits
     // A random ID assigned to query packets. Response packets must reply with the same ID.
@@ -77,6 +228,10 @@ impl DNSHeader {
         }
     }
 
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
     pub fn to_bytes(&self) -> [u8; 12] {
         let mut bytes = [0; 12];
         bytes[0..2].copy_from_slice(&self.id.to_be_bytes());
@@ -113,139 +268,554 @@ impl DNSHeader {
     }
 }
 
+// The record type carried by a question or a resource record. `UNKNOWN` preserves the raw type
+// number for anything we don't have a dedicated record-data representation for, so unrecognized
+// types can still be parsed, echoed back, and round-tripped without loss.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum QueryType {
+    A,
+    NS,
+    CNAME,
+    SOA,
+    PTR,
+    MX,
+    TXT,
+    AAAA,
+    SRV,
+    // EDNS0's OPT pseudo-record (RFC 6891). It never appears as a question type, only in the
+    // additional section, and repurposes the CLASS/TTL fields rather than carrying normal rdata.
+    OPT,
+    UNKNOWN(u16),
+}
+
+impl QueryType {
+    pub fn to_u16(self) -> u16 {
+        match self {
+            QueryType::A => 1,
+            QueryType::NS => 2,
+            QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::MX => 15,
+            QueryType::TXT => 16,
+            QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::OPT => 41,
+            QueryType::UNKNOWN(n) => n,
+        }
+    }
+
+    pub fn from_u16(n: u16) -> QueryType {
+        match n {
+            1 => QueryType::A,
+            2 => QueryType::NS,
+            5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            15 => QueryType::MX,
+            16 => QueryType::TXT,
+            28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            41 => QueryType::OPT,
+            n => QueryType::UNKNOWN(n),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct DNSQuestion {
     // A domain name represented as a sequence of labels, where each label consists of a length
     // octet followed by that number of octets.
     domain_name: Vec<String>,
     // The type of the query.
-    query_type: u16,
+    query_type: QueryType,
     // The class of the query.
     query_class: u16,
 }
 
 impl DNSQuestion {
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        for label in &self.domain_name {
-            bytes.push(label.len() as u8);
-            bytes.extend(label.as_bytes());
-        }
-        bytes.push(0x0);
-        bytes.extend(&self.query_type.to_be_bytes());
+    fn to_bytes(&self, bytes: &mut Vec<u8>, compressor: &mut NameCompressor) {
+        compressor.write_name(bytes, &self.domain_name);
+        bytes.extend(&self.query_type.to_u16().to_be_bytes());
         bytes.extend(&self.query_class.to_be_bytes());
-        bytes
     }
 
-    fn from_bytes(bytes: &[u8]) -> DNSQuestion {
-        let mut domain_name = Vec::new();
-        let mut i = 0;
-        while bytes[i] != 0x0 {
-            let label_length = bytes[i] as usize;
-            let label = String::from_utf8_lossy(&bytes[i + 1..i + 1 + label_length]);
-            domain_name.push(label.to_string());
-            i += 1 + label_length;
-        }
-        let query_type = u16::from_be_bytes([bytes[i + 1], bytes[i + 2]]);
-        let query_class = u16::from_be_bytes([bytes[i + 3], bytes[i + 4]]);
-        DNSQuestion {
+    fn from_bytes(buf: &mut PacketBuffer) -> Result<DNSQuestion, ProtocolError> {
+        let domain_name = buf.read_labels()?;
+        let query_type = QueryType::from_u16(buf.read_u16()?);
+        let query_class = buf.read_u16()?;
+        Ok(DNSQuestion {
             domain_name,
             query_type,
             query_class,
-        }
+        })
     }
 }
 
+// The parsed payload of a resource record, keyed by `QueryType`. Name-bearing variants store
+// labels rather than raw bytes so they participate in compression on the write side just like a
+// question or answer name does; the one exception is SRV's target, which is always written
+// uncompressed.
+#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum RData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    NS(Vec<String>),
+    CNAME(Vec<String>),
+    PTR(Vec<String>),
+    MX(u16, Vec<String>),
+    TXT(Vec<String>),
+    SOA {
+        m_name: Vec<String>,
+        r_name: Vec<String>,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: Vec<String>,
+    },
+    // The raw rdata for any type we don't otherwise model (including EDNS0's OPT). Note that this
+    // is only safe to re-emit verbatim when the bytes don't themselves contain a compression
+    // pointer: a pointer is an offset into the packet it was read from, so copying it unmodified
+    // into our own packet (which lays names out at different offsets) silently corrupts it. OPT's
+    // option data never contains names, so it's unaffected; a genuinely unknown type that happens
+    // to carry one is the risk this representation accepts.
+    UNKNOWN(Vec<u8>),
+}
+
+#[derive(Clone)]
 pub struct DNSAnswer {
     // A domain name represented as a sequence of labels, where each label consists of a length
     // octet followed by that number of octets.
-    domain_name: Vec<String>,
+    pub(crate) domain_name: Vec<String>,
     // The type of the query.
-    query_type: u16,
+    pub(crate) query_type: QueryType,
     // The class of the query.
-    query_class: u16,
+    pub(crate) query_class: u16,
     // The time to live of the resource record in seconds.
-    ttl: u32,
-    // The length of the resource record data in octets.
-    rdlength: u16,
-    // The resource record data.
-    rdata: Ipv4Addr,
+    pub(crate) ttl: u32,
+    // The resource record data. `rdlength` is derived from this when serializing rather than
+    // stored, since it's only ever a function of the encoded payload.
+    pub(crate) rdata: RData,
 }
 
 impl DNSAnswer {
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        for label in &self.domain_name {
-            bytes.push(label.len() as u8);
-            bytes.extend(label.as_bytes());
-        }
-        bytes.push(0x0);
-        bytes.extend(&self.query_type.to_be_bytes());
+    fn to_bytes(&self, bytes: &mut Vec<u8>, compressor: &mut NameCompressor) {
+        compressor.write_name(bytes, &self.domain_name);
+        bytes.extend(&self.query_type.to_u16().to_be_bytes());
         bytes.extend(&self.query_class.to_be_bytes());
         bytes.extend(&self.ttl.to_be_bytes());
-        bytes.extend(&self.rdlength.to_be_bytes());
-        bytes.extend(&self.rdata.octets());
-        bytes
+
+        // rdlength isn't known until the payload (which may itself contain compressed names) is
+        // written, so reserve the two bytes and backfill them afterwards.
+        let rdlength_pos = bytes.len();
+        bytes.extend(&0u16.to_be_bytes());
+        let rdata_start = bytes.len();
+        match &self.rdata {
+            RData::A(addr) => bytes.extend(&addr.octets()),
+            RData::AAAA(addr) => bytes.extend(&addr.octets()),
+            RData::NS(name) | RData::CNAME(name) | RData::PTR(name) => {
+                compressor.write_name(bytes, name);
+            }
+            RData::MX(preference, name) => {
+                bytes.extend(&preference.to_be_bytes());
+                compressor.write_name(bytes, name);
+            }
+            RData::TXT(strings) => {
+                for s in strings {
+                    bytes.push(s.len() as u8);
+                    bytes.extend(s.as_bytes());
+                }
+            }
+            RData::SOA {
+                m_name,
+                r_name,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                compressor.write_name(bytes, m_name);
+                compressor.write_name(bytes, r_name);
+                bytes.extend(&serial.to_be_bytes());
+                bytes.extend(&refresh.to_be_bytes());
+                bytes.extend(&retry.to_be_bytes());
+                bytes.extend(&expire.to_be_bytes());
+                bytes.extend(&minimum.to_be_bytes());
+            }
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                bytes.extend(&priority.to_be_bytes());
+                bytes.extend(&weight.to_be_bytes());
+                bytes.extend(&port.to_be_bytes());
+                NameCompressor::write_name_uncompressed(bytes, target);
+            }
+            RData::UNKNOWN(raw) => bytes.extend(raw),
+        }
+        let rdlength = (bytes.len() - rdata_start) as u16;
+        bytes[rdlength_pos..rdlength_pos + 2].copy_from_slice(&rdlength.to_be_bytes());
+    }
+
+    fn from_bytes(buf: &mut PacketBuffer) -> Result<DNSAnswer, ProtocolError> {
+        let domain_name = buf.read_labels()?;
+        let query_type = QueryType::from_u16(buf.read_u16()?);
+        let query_class = buf.read_u16()?;
+        let ttl = buf.read_u32()?;
+        let rdlength = buf.read_u16()?;
+
+        let rdata = match query_type {
+            QueryType::A => {
+                let raw = buf.read_bytes(rdlength as usize)?;
+                if raw.len() != 4 {
+                    return Err(ProtocolError);
+                }
+                RData::A(Ipv4Addr::new(raw[0], raw[1], raw[2], raw[3]))
+            }
+            QueryType::AAAA => {
+                let raw = buf.read_bytes(rdlength as usize)?;
+                let octets: [u8; 16] = raw.try_into().map_err(|_| ProtocolError)?;
+                RData::AAAA(Ipv6Addr::from(octets))
+            }
+            QueryType::NS => RData::NS(buf.read_labels()?),
+            QueryType::CNAME => RData::CNAME(buf.read_labels()?),
+            QueryType::PTR => RData::PTR(buf.read_labels()?),
+            QueryType::MX => {
+                let preference = buf.read_u16()?;
+                let name = buf.read_labels()?;
+                RData::MX(preference, name)
+            }
+            QueryType::TXT => {
+                let raw = buf.read_bytes(rdlength as usize)?;
+                let mut strings = Vec::new();
+                let mut i = 0;
+                while i < raw.len() {
+                    let len = raw[i] as usize;
+                    let s = raw.get(i + 1..i + 1 + len).ok_or(ProtocolError)?;
+                    strings.push(String::from_utf8_lossy(s).to_string());
+                    i += 1 + len;
+                }
+                RData::TXT(strings)
+            }
+            QueryType::SOA => {
+                let m_name = buf.read_labels()?;
+                let r_name = buf.read_labels()?;
+                let serial = buf.read_u32()?;
+                let refresh = buf.read_u32()?;
+                let retry = buf.read_u32()?;
+                let expire = buf.read_u32()?;
+                let minimum = buf.read_u32()?;
+                RData::SOA {
+                    m_name,
+                    r_name,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                }
+            }
+            QueryType::SRV => {
+                let priority = buf.read_u16()?;
+                let weight = buf.read_u16()?;
+                let port = buf.read_u16()?;
+                let target = buf.read_labels()?;
+                RData::SRV {
+                    priority,
+                    weight,
+                    port,
+                    target,
+                }
+            }
+            QueryType::OPT | QueryType::UNKNOWN(_) => {
+                RData::UNKNOWN(buf.read_bytes(rdlength as usize)?.to_vec())
+            }
+        };
+
+        Ok(DNSAnswer {
+            domain_name,
+            query_type,
+            query_class,
+            ttl,
+            rdata,
+        })
     }
 }
 
+// The default payload size we advertise in our own EDNS0 OPT record, echoed back whenever a
+// client's query carries one.
+const OUR_UDP_PAYLOAD_SIZE: u16 = 4096;
+
 pub struct DNSQuery {
     header: DNSHeader,
-    question_section: DNSQuestion,
+    questions: Vec<DNSQuestion>,
+    // Additional-section records carried by the query, namely an EDNS0 OPT pseudo-record if the
+    // client sent one.
+    additionals: Vec<DNSAnswer>,
 }
 
 impl DNSQuery {
-    pub fn new(id: u16, question: DNSQuestion) -> DNSQuery {
+    pub fn new(id: u16, questions: Vec<DNSQuestion>) -> DNSQuery {
         DNSQuery {
             header: DNSHeader::new(id, false),
-            question_section: question,
+            questions,
+            additionals: Vec::new(),
         }
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Result<DNSQuery, ProtocolError> {
         let header = DNSHeader::from_bytes(bytes)?;
-        let question_section = DNSQuestion::from_bytes(&bytes[12..]);
-        Ok(DNSQuery::new(header.id, question_section))
+        let mut buf = PacketBuffer::new(bytes);
+        buf.seek(12);
+        let questions = (0..header.qdcount)
+            .map(|_| DNSQuestion::from_bytes(&mut buf))
+            .collect::<Result<Vec<_>, _>>()?;
+        for _ in 0..header.ancount {
+            DNSAnswer::from_bytes(&mut buf)?;
+        }
+        for _ in 0..header.nscount {
+            DNSAnswer::from_bytes(&mut buf)?;
+        }
+        let additionals = (0..header.arcount)
+            .map(|_| DNSAnswer::from_bytes(&mut buf))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(DNSQuery {
+            header,
+            questions,
+            additionals,
+        })
+    }
+
+    // Used when building an outbound query to an upstream resolver, which should always ask for
+    // recursion regardless of what the original client sent us.
+    pub fn with_recursion_desired(mut self) -> DNSQuery {
+        self.header.rd = 1;
+        self
+    }
+
+    // The UDP payload size the client advertised via an EDNS0 OPT record, if it sent one. The
+    // CLASS field of an OPT record is repurposed by RFC 6891 to carry this instead of a record
+    // class.
+    pub fn edns_udp_payload_size(&self) -> Option<u16> {
+        self.additionals
+            .iter()
+            .find(|record| record.query_type == QueryType::OPT)
+            .map(|record| record.query_class)
     }
 
-    #[allow(dead_code)]
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.header.to_bytes().to_vec();
-        bytes.extend(self.question_section.to_bytes());
+        let mut header = self.header.clone();
+        header.qdcount = self.questions.len() as u16;
+        header.arcount = self.additionals.len() as u16;
+        let mut bytes = header.to_bytes().to_vec();
+        let mut compressor = NameCompressor::new();
+        for question in &self.questions {
+            question.to_bytes(&mut bytes, &mut compressor);
+        }
+        for additional in &self.additionals {
+            additional.to_bytes(&mut bytes, &mut compressor);
+        }
         bytes
     }
 }
 
 pub struct DNSResponse {
     header: DNSHeader,
-    question_section: DNSQuestion,
-    answer_section: DNSAnswer,
+    questions: Vec<DNSQuestion>,
+    answers: Vec<DNSAnswer>,
+    authorities: Vec<DNSAnswer>,
+    additionals: Vec<DNSAnswer>,
 }
 
 impl DNSResponse {
-    pub fn for_request(query: DNSQuery) -> DNSResponse {
+    // Builds the reply to `query`. A question whose name falls inside a zone in `zones` is
+    // answered authoritatively from that zone. Otherwise, when `resolver` is configured and the
+    // client asked for recursion, the question is forwarded upstream and the real answers are
+    // relayed back; failing both, we fall back to the stub answer below.
+    pub fn for_request(
+        query: DNSQuery,
+        resolver: Option<&str>,
+        zones: Option<&crate::authority::ZoneRegistry>,
+    ) -> DNSResponse {
         let mut header = DNSHeader::new(query.header.id, true);
-        header.qdcount = 1;
-        // TODO: Actually compute these values
-        let answer = DNSAnswer {
-            domain_name: query.question_section.domain_name.clone(),
-            query_type: query.question_section.query_type,
-            query_class: query.question_section.query_class,
-            ttl: 60,
-            rdlength: 4,
-            rdata: Ipv4Addr::new(8, 8, 8, 8),
-        };
-        header.ancount = 1;
+        header.opcode = query.header.opcode;
+        header.rd = query.header.rd;
+
+        // We only implement standard query (opcode 0); anything else is a conformant NOTIMP.
+        if query.header.opcode != 0 {
+            header.rcode = 4;
+            return DNSResponse {
+                header,
+                questions: query.questions,
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+        }
+
+        let recurse = resolver.is_some() && query.header.rd == 1;
+        if recurse {
+            header.ra = 1;
+        }
+
+        let mut answers = Vec::new();
+        let mut authorities = Vec::new();
+        let mut additionals = Vec::new();
+
+        for question in &query.questions {
+            if let Some(zone) = zones.and_then(|registry| registry.find(&question.domain_name)) {
+                header.aa = 1;
+                let matched = zone.lookup(&question.domain_name, question.query_type);
+                if matched.is_empty() {
+                    authorities.push(zone.soa_record());
+                    if !zone.exists(&question.domain_name) {
+                        // The name itself isn't in the zone at all, not just lacking this type.
+                        header.rcode = 3;
+                    }
+                } else {
+                    answers.extend(matched.into_iter().cloned());
+                }
+            } else if let Some(resolver_addr) = resolver.filter(|_| recurse) {
+                if let Ok(resolved) = crate::resolver::resolve(resolver_addr, question) {
+                    answers.extend(resolved.answers);
+                    authorities.extend(resolved.authorities);
+                    // We push our own OPT record below; relaying upstream's as well would give
+                    // the reply two, which is malformed.
+                    additionals.extend(
+                        resolved
+                            .additionals
+                            .into_iter()
+                            .filter(|record| record.query_type != QueryType::OPT),
+                    );
+                }
+            } else {
+                // No zone claims this name and we're not forwarding (recursion wasn't requested,
+                // or no upstream resolver is configured): fall back to a stub A answer.
+                answers.push(DNSAnswer {
+                    domain_name: question.domain_name.clone(),
+                    query_type: question.query_type,
+                    query_class: question.query_class,
+                    ttl: 60,
+                    rdata: RData::A(Ipv4Addr::new(8, 8, 8, 8)),
+                });
+            }
+        }
+
+        if answers.is_empty() && authorities.is_empty() && !query.questions.is_empty() {
+            header.rcode = 3;
+        }
+
+        // Echo an OPT record back, advertising our own UDP payload size, whenever the client
+        // sent one.
+        if query.edns_udp_payload_size().is_some() {
+            additionals.push(DNSAnswer {
+                domain_name: Vec::new(),
+                query_type: QueryType::OPT,
+                query_class: OUR_UDP_PAYLOAD_SIZE,
+                ttl: 0,
+                rdata: RData::UNKNOWN(Vec::new()),
+            });
+        }
+
+        DNSResponse {
+            header,
+            questions: query.questions,
+            answers,
+            authorities,
+            additionals,
+        }
+    }
+
+    // Builds a minimal FORMERR reply for a packet whose header parsed but whose body didn't, so
+    // we still answer malformed queries instead of silently dropping them.
+    pub fn format_error(id: u16) -> DNSResponse {
+        let mut header = DNSHeader::new(id, true);
+        header.rcode = 1;
         DNSResponse {
             header,
-            question_section: query.question_section,
-            answer_section: answer,
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
         }
     }
 
+    pub fn from_bytes(bytes: &[u8]) -> Result<DNSResponse, ProtocolError> {
+        let header = DNSHeader::from_bytes(bytes)?;
+        let mut buf = PacketBuffer::new(bytes);
+        buf.seek(12);
+        let questions = (0..header.qdcount)
+            .map(|_| DNSQuestion::from_bytes(&mut buf))
+            .collect::<Result<Vec<_>, _>>()?;
+        let answers = (0..header.ancount)
+            .map(|_| DNSAnswer::from_bytes(&mut buf))
+            .collect::<Result<Vec<_>, _>>()?;
+        let authorities = (0..header.nscount)
+            .map(|_| DNSAnswer::from_bytes(&mut buf))
+            .collect::<Result<Vec<_>, _>>()?;
+        let additionals = (0..header.arcount)
+            .map(|_| DNSAnswer::from_bytes(&mut buf))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(DNSResponse {
+            header,
+            questions,
+            answers,
+            authorities,
+            additionals,
+        })
+    }
+
+    // Consumes the response, handing back its answer/authority/additional sections. Used by the
+    // resolver to relay all three from an upstream reply without exposing the rest of
+    // `DNSResponse`.
+    pub fn into_sections(self) -> (Vec<DNSAnswer>, Vec<DNSAnswer>, Vec<DNSAnswer>) {
+        (self.answers, self.authorities, self.additionals)
+    }
+
+    // Marks the response as truncated and drops its resource-record sections. Used on the UDP
+    // path when the full response would exceed the transport's size limit, so a conformant
+    // client sees TC=1 and retries the query over TCP to get the untruncated answer.
+    pub fn truncate(&mut self) {
+        self.header.tc = 1;
+        self.answers.clear();
+        self.authorities.clear();
+        // Keep any echoed OPT record: RFC 6891 wants EDNS state preserved across a truncated
+        // reply so the client carries it into the TCP retry.
+        self.additionals.retain(|record| record.query_type == QueryType::OPT);
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.header.to_bytes().to_vec();
-        bytes.extend(self.question_section.to_bytes());
-        bytes.extend(self.answer_section.to_bytes());
+        let mut header = self.header.clone();
+        header.qdcount = self.questions.len() as u16;
+        header.ancount = self.answers.len() as u16;
+        header.nscount = self.authorities.len() as u16;
+        header.arcount = self.additionals.len() as u16;
+        let mut bytes = header.to_bytes().to_vec();
+        let mut compressor = NameCompressor::new();
+        for question in &self.questions {
+            question.to_bytes(&mut bytes, &mut compressor);
+        }
+        for answer in &self.answers {
+            answer.to_bytes(&mut bytes, &mut compressor);
+        }
+        for authority in &self.authorities {
+            authority.to_bytes(&mut bytes, &mut compressor);
+        }
+        for additional in &self.additionals {
+            additional.to_bytes(&mut bytes, &mut compressor);
+        }
         bytes
     }
 }
@@ -0,0 +1,50 @@
+use std::net::UdpSocket;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::protocol::{DNSAnswer, DNSQuery, DNSQuestion, DNSResponse, ProtocolError};
+
+// How long to wait for the upstream resolver before giving up on a query. `run_udp_server` calls
+// `resolve` inline on its single thread, so a hung upstream would otherwise wedge every client.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The resource-record sections of an upstream reply, carried back so the caller can relay all of
+// them into our own response rather than just the answers.
+pub struct Resolved {
+    pub answers: Vec<DNSAnswer>,
+    pub authorities: Vec<DNSAnswer>,
+    pub additionals: Vec<DNSAnswer>,
+}
+
+// Forwards a single question to the configured upstream resolver over UDP and returns its
+// answer, authority, and additional records. Each call gets its own socket and query ID,
+// independent of the ID the original client used, since the upstream exchange is entirely our
+// own affair.
+pub fn resolve(resolver_addr: &str, question: &DNSQuestion) -> Result<Resolved, ProtocolError> {
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|_| ProtocolError)?;
+    socket
+        .set_read_timeout(Some(UPSTREAM_TIMEOUT))
+        .map_err(|_| ProtocolError)?;
+    let query = DNSQuery::new(upstream_id(), vec![question.clone()]).with_recursion_desired();
+    socket
+        .send_to(&query.to_bytes(), resolver_addr)
+        .map_err(|_| ProtocolError)?;
+
+    let mut buf = [0; 512];
+    let (size, _) = socket.recv_from(&mut buf).map_err(|_| ProtocolError)?;
+    let response = DNSResponse::from_bytes(&buf[0..size])?;
+    let (answers, authorities, additionals) = response.into_sections();
+    Ok(Resolved {
+        answers,
+        authorities,
+        additionals,
+    })
+}
+
+// A cheap, non-cryptographic ID for outbound queries. It only needs to be unlikely to collide
+// with another in-flight query on the same socket, not unpredictable.
+fn upstream_id() -> u16 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos() as u16)
+        .unwrap_or(0)
+}